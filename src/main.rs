@@ -1,10 +1,13 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::iter;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 #[allow(unused_imports)]
 use std::net::UdpSocket;
 use std::str::FromStr;
+use std::time::Duration;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 enum OpCode {
     #[default]
     /// Query [RFC1035]
@@ -13,8 +16,9 @@ enum OpCode {
     IQuery,
     /// Status [RFC1035]
     Status,
-    /// Unassigned
-    Unassigned,
+    /// any OPCODE value without a name assigned to it, kept verbatim so it
+    /// can be echoed back (RFC1035 requires OPCODE to be copied into the reply)
+    Unassigned(u8),
     /// Notify [RFC1996]
     Notify,
     /// Update [RFC2136]
@@ -24,16 +28,30 @@ enum OpCode {
 }
 
 impl OpCode {
-    fn to_bytes(&self) -> u8 {
+    fn to_bytes(self) -> u8 {
         match self {
             OpCode::Query => 0,
             OpCode::IQuery => 1,
             OpCode::Status => 2,
-            OpCode::Unassigned => unreachable!(),
+            OpCode::Unassigned(b) => b,
             OpCode::Notify => 4,
             OpCode::Update => 5,
             OpCode::DSO => 6,
-            _ => unreachable!(),
+        }
+    }
+
+    /// Inverse of [`OpCode::to_bytes`]. Values outside the known range (and
+    /// the reserved value 3) come back as `Unassigned`, carrying the raw
+    /// byte so it round-trips instead of being collapsed to one value.
+    fn from_u8(b: u8) -> Self {
+        match b {
+            0 => OpCode::Query,
+            1 => OpCode::IQuery,
+            2 => OpCode::Status,
+            4 => OpCode::Notify,
+            5 => OpCode::Update,
+            6 => OpCode::DSO,
+            _ => OpCode::Unassigned(b),
         }
     }
 }
@@ -53,6 +71,8 @@ enum RCode {
     NotImp,
     /// Refused Query Refused [RFC1035]
     Refused,
+    /// any RCODE value without a name assigned to it
+    Unassigned,
 }
 
 impl RCode {
@@ -64,11 +84,35 @@ impl RCode {
             RCode::NXDomain => 3,
             RCode::NotImp => 4,
             RCode::Refused => 5,
-            _ => unreachable!(),
+            RCode::Unassigned => 6,
+        }
+    }
+
+    /// Inverse of [`RCode::to_bytes`].
+    fn from_u8(b: u8) -> Self {
+        match b {
+            0 => RCode::NoError,
+            1 => RCode::FormErr,
+            2 => RCode::ServFail,
+            3 => RCode::NXDomain,
+            4 => RCode::NotImp,
+            5 => RCode::Refused,
+            _ => RCode::Unassigned,
         }
     }
 }
 
+/// Failure modes when decoding a raw DNS message.
+#[derive(Debug)]
+enum ParseError {
+    /// the buffer ended before all expected bytes were read
+    UnexpectedEof,
+    /// a label's bytes were not valid UTF-8
+    InvalidLabel,
+    /// a compression pointer jumped forward or back onto itself
+    PointerLoop,
+}
+
 #[derive(Debug)]
 struct DNSHeader {
     /// should be random
@@ -187,9 +231,51 @@ impl DNSHeader {
 
         res
     }
+
+    /// Decode the 12-byte header, the inverse of [`DNSHeader::to_bytes`].
+    fn from_bytes(buf: &[u8]) -> Result<Self, ParseError> {
+        if buf.len() < 12 {
+            return Err(ParseError::UnexpectedEof);
+        }
+
+        let packet_id = u16::from_be_bytes([buf[0], buf[1]]);
+
+        let b = buf[2];
+        let qr = b & 0b1000_0000 != 0;
+        let opcode = OpCode::from_u8((b >> 3) & 0b0000_1111);
+        let aa = b & 0b0000_0100 != 0;
+        let tc = b & 0b0000_0010 != 0;
+        let rd = b & 0b0000_0001 != 0;
+
+        let b = buf[3];
+        let ra = b & 0b1000_0000 != 0;
+        let z = (b >> 4) & 0b0000_0111;
+        let rcode = RCode::from_u8(b & 0b0000_1111);
+
+        let qdcount = u16::from_be_bytes([buf[4], buf[5]]);
+        let ancount = u16::from_be_bytes([buf[6], buf[7]]);
+        let nscount = u16::from_be_bytes([buf[8], buf[9]]);
+        let arcount = u16::from_be_bytes([buf[10], buf[11]]);
+
+        Ok(Self {
+            packet_id,
+            qr,
+            opcode,
+            aa,
+            tc,
+            rd,
+            ra,
+            z,
+            rcode,
+            qdcount,
+            ancount,
+            nscount,
+            arcount,
+        })
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 enum RRType {
     #[default]
     /// A 1 a host address
@@ -224,10 +310,18 @@ enum RRType {
     MX,
     /// TXT 16 text strings
     TXT,
+    /// AAAA 28 an IPv6 host address [RFC3596]
+    AAAA,
+    /// SRV 33 a service location record [RFC2782]
+    SRV,
+    /// OPT 41 a pseudo-RR carrying EDNS0 metadata instead of a real record [RFC6891]
+    OPT,
+    /// any TYPE value without a name assigned to it
+    Unassigned,
 }
 
 impl RRType {
-    fn to_bytes(&self) -> [u8; 2] {
+    fn to_bytes(self) -> [u8; 2] {
         let b: u16 = match self {
             RRType::A => 1,
             RRType::NS => 2,
@@ -242,15 +336,45 @@ impl RRType {
             RRType::WKS => 11,
             RRType::PTR => 12,
             RRType::HInfo => 13,
+            RRType::MInfo => 14,
             RRType::MX => 15,
             RRType::TXT => 16,
-            _ => unreachable!(),
+            RRType::AAAA => 28,
+            RRType::SRV => 33,
+            RRType::OPT => 41,
+            RRType::Unassigned => 0,
         };
         b.to_be_bytes()
     }
+
+    /// Inverse of [`RRType::to_bytes`].
+    fn from_u16(v: u16) -> Self {
+        match v {
+            1 => RRType::A,
+            2 => RRType::NS,
+            3 => RRType::MD,
+            4 => RRType::MF,
+            5 => RRType::CName,
+            6 => RRType::SOA,
+            7 => RRType::MB,
+            8 => RRType::MG,
+            9 => RRType::MR,
+            10 => RRType::NULL,
+            11 => RRType::WKS,
+            12 => RRType::PTR,
+            13 => RRType::HInfo,
+            14 => RRType::MInfo,
+            15 => RRType::MX,
+            16 => RRType::TXT,
+            28 => RRType::AAAA,
+            33 => RRType::SRV,
+            41 => RRType::OPT,
+            _ => RRType::Unassigned,
+        }
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 enum Class {
     #[default]
     /// IN 1 the Internet
@@ -261,21 +385,34 @@ enum Class {
     CH,
     /// HS 4 Hesiod [Dyer 87]
     HS,
+    /// any CLASS value without a name assigned to it
+    Unassigned,
 }
 impl Class {
-    fn to_bytes(&self) -> [u8; 2] {
+    fn to_bytes(self) -> [u8; 2] {
         let b: u16 = match self {
             Class::IN => 1,
             Class::CS => 2,
             Class::CH => 3,
             Class::HS => 4,
-            _ => unreachable!(),
+            Class::Unassigned => 0,
         };
         b.to_be_bytes()
     }
+
+    /// Inverse of [`Class::to_bytes`].
+    fn from_u16(v: u16) -> Self {
+        match v {
+            1 => Class::IN,
+            2 => Class::CS,
+            3 => Class::CH,
+            4 => Class::HS,
+            _ => Class::Unassigned,
+        }
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 struct Label {
     length: u8,
     value: String,
@@ -307,13 +444,15 @@ impl Label {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 struct CName(Vec<Label>);
 
 impl FromStr for CName {
     type Err = LabelParsingError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // a trailing dot marks a fully-qualified name and carries no label of its own
+        let s = s.strip_suffix('.').unwrap_or(s);
         let r: Result<Vec<_>, Self::Err> = s.split('.').map(Label::from_str).collect();
         match r {
             Ok(c) => Ok(Self(c)),
@@ -343,28 +482,961 @@ impl CName {
             .chain(iter::once(0))
             .collect()
     }
+
+    /// Decode a sequence of length-prefixed labels starting at `offset`,
+    /// stopping at the terminating zero octet or following a compression
+    /// pointer (RFC1035 §4.1.4) into an earlier part of the message.
+    /// Returns the name along with the offset of the byte right after it
+    /// *in the original message* (i.e. right after the pointer, not after
+    /// wherever the pointer led).
+    fn from_bytes(buf: &[u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let mut labels = Vec::new();
+        let mut pos = offset;
+        let mut after_pointer = None;
+        let mut visited = std::collections::HashSet::new();
+
+        loop {
+            let len = *buf.get(pos).ok_or(ParseError::UnexpectedEof)? as usize;
+
+            if len & 0xC0 == 0xC0 {
+                let lo = *buf.get(pos + 1).ok_or(ParseError::UnexpectedEof)? as usize;
+                let target = ((len & 0x3F) << 8) | lo;
+                if after_pointer.is_none() {
+                    after_pointer = Some(pos + 2);
+                }
+                if target >= pos || !visited.insert(target) {
+                    return Err(ParseError::PointerLoop);
+                }
+                pos = target;
+                continue;
+            }
+
+            if len == 0 {
+                pos += 1;
+                break;
+            }
+
+            let start = pos + 1;
+            let end = start + len;
+            let bytes = buf.get(start..end).ok_or(ParseError::UnexpectedEof)?;
+            let value = String::from_utf8(bytes.to_vec()).map_err(|_| ParseError::InvalidLabel)?;
+            labels.push(Label {
+                length: len as u8,
+                value,
+            });
+            pos = end;
+        }
+
+        Ok((Self(labels), after_pointer.unwrap_or(pos)))
+    }
+
+    /// Encode, replacing any already-written suffix with a 2-byte
+    /// compression pointer (RFC1035 §4.1.4) instead of repeating its labels.
+    /// `offset` is the position this name will occupy in the message; every
+    /// suffix written out gets recorded in `table` so later names (e.g. an
+    /// answer's owner name repeating the question's name) can point back to
+    /// it.
+    fn to_bytes_compressed(&self, offset: u16, table: &mut HashMap<String, u16>) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        for (i, label) in self.0.iter().enumerate() {
+            let suffix = self.0[i..]
+                .iter()
+                .map(|l| l.value.as_str())
+                .collect::<Vec<_>>()
+                .join(".");
+
+            if let Some(&pointer) = table.get(&suffix) {
+                out.extend((0xC000_u16 | pointer).to_be_bytes());
+                return out;
+            }
+
+            table.insert(suffix, offset + out.len() as u16);
+            out.extend(label.to_bytes());
+        }
+
+        out.push(0);
+        out
+    }
 }
 
-#[derive(Debug, Default)]
+/// SOA parameters (RFC1035 §3.3.13). Shared between the RR payload and a
+/// loaded `Zone`'s own authority data, since both need the same seven
+/// fields.
+#[derive(Debug, Clone)]
+struct SoaParams {
+    mname: CName,
+    rname: CName,
+    serial: u32,
+    refresh: u32,
+    retry: u32,
+    expire: u32,
+    minimum: u32,
+}
+
+impl SoaParams {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut r = self.mname.to_bytes();
+        r.extend(self.rname.to_bytes());
+        r.extend(self.serial.to_be_bytes());
+        r.extend(self.refresh.to_be_bytes());
+        r.extend(self.retry.to_be_bytes());
+        r.extend(self.expire.to_be_bytes());
+        r.extend(self.minimum.to_be_bytes());
+        r
+    }
+
+    fn from_bytes(buf: &[u8], offset: usize) -> Result<Self, ParseError> {
+        let (mname, pos) = CName::from_bytes(buf, offset)?;
+        let (rname, pos) = CName::from_bytes(buf, pos)?;
+        let field = |n: usize| -> Result<u32, ParseError> {
+            Ok(u32::from_be_bytes(
+                buf.get(pos + n..pos + n + 4)
+                    .ok_or(ParseError::UnexpectedEof)?
+                    .try_into()
+                    .unwrap(),
+            ))
+        };
+        Ok(Self {
+            mname,
+            rname,
+            serial: field(0)?,
+            refresh: field(4)?,
+            retry: field(8)?,
+            expire: field(12)?,
+            minimum: field(16)?,
+        })
+    }
+}
+
+/// The type-specific payload of a resource record (RFC1035 §3.3 and friends).
+#[derive(Debug, Clone)]
+enum RData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    NS(CName),
+    CName(CName),
+    MX {
+        preference: u16,
+        exchange: CName,
+    },
+    PTR(CName),
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: CName,
+    },
+    SOA(SoaParams),
+    TXT(Vec<String>),
+    /// RDATA for a type we don't otherwise interpret, kept verbatim so it
+    /// can still be relayed on (e.g. answers a forwarder received upstream).
+    Raw(RRType, Vec<u8>),
+}
+
+impl Default for RData {
+    fn default() -> Self {
+        RData::A(Ipv4Addr::UNSPECIFIED)
+    }
+}
+
+/// Encode `s` as one or more RFC1035 `<character-string>`s: a length octet
+/// followed by that many bytes, the length capped at 255 since the octet
+/// can't say more. Longer strings are split across multiple character-strings
+/// rather than letting the length wrap.
+fn txt_character_strings(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    if bytes.is_empty() {
+        return vec![0];
+    }
+    bytes
+        .chunks(255)
+        .flat_map(|chunk| iter::once(chunk.len() as u8).chain(chunk.iter().copied()))
+        .collect()
+}
+
+impl RData {
+    /// The `RRType` this payload is carried under.
+    fn rrtype(&self) -> RRType {
+        match self {
+            RData::A(_) => RRType::A,
+            RData::AAAA(_) => RRType::AAAA,
+            RData::NS(_) => RRType::NS,
+            RData::CName(_) => RRType::CName,
+            RData::MX { .. } => RRType::MX,
+            RData::PTR(_) => RRType::PTR,
+            RData::SRV { .. } => RRType::SRV,
+            RData::SOA(_) => RRType::SOA,
+            RData::TXT(_) => RRType::TXT,
+            RData::Raw(rrtype, _) => *rrtype,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            RData::A(addr) => addr.octets().to_vec(),
+            RData::AAAA(addr) => addr.octets().to_vec(),
+            RData::NS(name) => name.to_bytes(),
+            RData::CName(name) => name.to_bytes(),
+            RData::MX {
+                preference,
+                exchange,
+            } => {
+                let mut r = preference.to_be_bytes().to_vec();
+                r.extend(exchange.to_bytes());
+                r
+            }
+            RData::PTR(name) => name.to_bytes(),
+            RData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                let mut r = priority.to_be_bytes().to_vec();
+                r.extend(weight.to_be_bytes());
+                r.extend(port.to_be_bytes());
+                r.extend(target.to_bytes());
+                r
+            }
+            RData::SOA(soa) => soa.to_bytes(),
+            RData::TXT(strings) => strings.iter().flat_map(|s| txt_character_strings(s)).collect(),
+            RData::Raw(_, bytes) => bytes.clone(),
+        }
+    }
+
+    /// Decode `rdata` (the RDLENGTH-bounded slice of the record) according
+    /// to `rrtype`. Names inside the RDATA may use message compression, so
+    /// embedded `CName`s are parsed from `buf` at `offset`, not from `rdata`
+    /// alone.
+    fn from_bytes(
+        buf: &[u8],
+        offset: usize,
+        rrtype: RRType,
+        rdata: &[u8],
+    ) -> Result<Self, ParseError> {
+        match rrtype {
+            RRType::A => {
+                let octets: [u8; 4] = rdata.try_into().map_err(|_| ParseError::UnexpectedEof)?;
+                Ok(RData::A(Ipv4Addr::from(octets)))
+            }
+            RRType::AAAA => {
+                let octets: [u8; 16] = rdata.try_into().map_err(|_| ParseError::UnexpectedEof)?;
+                Ok(RData::AAAA(Ipv6Addr::from(octets)))
+            }
+            RRType::NS => {
+                let (name, _) = CName::from_bytes(buf, offset)?;
+                Ok(RData::NS(name))
+            }
+            RRType::CName => {
+                let (name, _) = CName::from_bytes(buf, offset)?;
+                Ok(RData::CName(name))
+            }
+            RRType::MX => {
+                let preference = u16::from_be_bytes(
+                    rdata.get(0..2).ok_or(ParseError::UnexpectedEof)?.try_into().unwrap(),
+                );
+                let (exchange, _) = CName::from_bytes(buf, offset + 2)?;
+                Ok(RData::MX {
+                    preference,
+                    exchange,
+                })
+            }
+            RRType::PTR => {
+                let (name, _) = CName::from_bytes(buf, offset)?;
+                Ok(RData::PTR(name))
+            }
+            RRType::SRV => {
+                let priority = u16::from_be_bytes(
+                    rdata.get(0..2).ok_or(ParseError::UnexpectedEof)?.try_into().unwrap(),
+                );
+                let weight = u16::from_be_bytes(
+                    rdata.get(2..4).ok_or(ParseError::UnexpectedEof)?.try_into().unwrap(),
+                );
+                let port = u16::from_be_bytes(
+                    rdata.get(4..6).ok_or(ParseError::UnexpectedEof)?.try_into().unwrap(),
+                );
+                let (target, _) = CName::from_bytes(buf, offset + 6)?;
+                Ok(RData::SRV {
+                    priority,
+                    weight,
+                    port,
+                    target,
+                })
+            }
+            RRType::SOA => Ok(RData::SOA(SoaParams::from_bytes(buf, offset)?)),
+            RRType::TXT => {
+                let mut strings = Vec::new();
+                let mut pos = 0;
+                while pos < rdata.len() {
+                    let len = rdata[pos] as usize;
+                    let start = pos + 1;
+                    let end = start + len;
+                    let bytes = rdata.get(start..end).ok_or(ParseError::UnexpectedEof)?;
+                    strings
+                        .push(String::from_utf8(bytes.to_vec()).map_err(|_| ParseError::InvalidLabel)?);
+                    pos = end;
+                }
+                Ok(RData::TXT(strings))
+            }
+            other => Ok(RData::Raw(other, rdata.to_vec())),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 struct ResourceRecord {
-    cname: CName,
-    rrtype: RRType,
+    name: CName,
     class: Class,
+    ttl: u32,
+    rdata: RData,
+}
+
+impl ResourceRecord {
+    /// Encode NAME, TYPE, CLASS, TTL, RDLENGTH and RDATA, in that order,
+    /// compressing NAME against whatever suffixes `table` already has
+    /// recorded (see [`CName::to_bytes_compressed`]).
+    fn to_bytes(&self, offset: u16, table: &mut HashMap<String, u16>) -> Vec<u8> {
+        let mut r = self.name.to_bytes_compressed(offset, table);
+        r.extend(self.rdata.rrtype().to_bytes());
+        r.extend(self.class.to_bytes());
+        r.extend(self.ttl.to_be_bytes());
+
+        let rdata = self.rdata.to_bytes();
+        r.extend((rdata.len() as u16).to_be_bytes());
+        r.extend(rdata);
+
+        r
+    }
+
+    /// Decode NAME, TYPE, CLASS, TTL, RDLENGTH and RDATA, returning the
+    /// record along with the offset of the byte right after it.
+    fn from_bytes(buf: &[u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let (name, pos) = CName::from_bytes(buf, offset)?;
+        let rrtype = RRType::from_u16(u16::from_be_bytes(
+            buf.get(pos..pos + 2).ok_or(ParseError::UnexpectedEof)?.try_into().unwrap(),
+        ));
+        let class = Class::from_u16(u16::from_be_bytes(
+            buf.get(pos + 2..pos + 4)
+                .ok_or(ParseError::UnexpectedEof)?
+                .try_into()
+                .unwrap(),
+        ));
+        let ttl = u32::from_be_bytes(
+            buf.get(pos + 4..pos + 8)
+                .ok_or(ParseError::UnexpectedEof)?
+                .try_into()
+                .unwrap(),
+        );
+        let rdlength = u16::from_be_bytes(
+            buf.get(pos + 8..pos + 10)
+                .ok_or(ParseError::UnexpectedEof)?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+
+        let rdata_start = pos + 10;
+        let rdata_end = rdata_start + rdlength;
+        let rdata_buf = buf.get(rdata_start..rdata_end).ok_or(ParseError::UnexpectedEof)?;
+        let rdata = RData::from_bytes(buf, rdata_start, rrtype, rdata_buf)?;
+
+        Ok((
+            Self {
+                name,
+                class,
+                ttl,
+                rdata,
+            },
+            rdata_end,
+        ))
+    }
+}
+
+/// Failure modes when loading a zone file.
+#[derive(Debug)]
+enum ZoneError {
+    Io(std::io::Error),
+    /// the file had no SOA record to anchor the zone's apex
+    MissingSoa,
+    /// a line didn't parse as `name TTL CLASS TYPE RDATA`
+    MalformedLine(String),
+}
+
+impl fmt::Display for ZoneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZoneError::Io(e) => write!(f, "I/O error: {}", e),
+            ZoneError::MissingSoa => write!(f, "zone file has no SOA record"),
+            ZoneError::MalformedLine(line) => write!(f, "malformed zone line: {:?}", line),
+        }
+    }
+}
+
+/// An authoritative zone: a domain apex, its SOA parameters, and whatever
+/// resource records were loaded under it from a zone file, keyed by
+/// (owner name, type).
+#[derive(Debug)]
+struct Zone {
+    apex: CName,
+    soa: SoaParams,
+    records: HashMap<(String, RRType), Vec<ResourceRecord>>,
+}
+
+impl Zone {
+    /// Parse a zone file of lines `name TTL CLASS TYPE RDATA`. The apex and
+    /// SOA parameters come from the zone's own SOA record.
+    fn load(path: &str) -> Result<Self, ZoneError> {
+        let contents = std::fs::read_to_string(path).map_err(ZoneError::Io)?;
+
+        let mut apex = None;
+        let mut soa = None;
+        let mut records: HashMap<(String, RRType), Vec<ResourceRecord>> = HashMap::new();
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let malformed = || ZoneError::MalformedLine(line.to_string());
+            let mut fields = line.split_whitespace();
+            let name: CName = next_field(&mut fields, malformed)?;
+            let ttl: u32 = next_field(&mut fields, malformed)?;
+            let class = fields.next().ok_or_else(malformed)?;
+            let rtype = fields.next().ok_or_else(malformed)?;
+            let mut rdata_fields = fields;
+
+            if class != "IN" {
+                return Err(malformed());
+            }
+
+            let rdata = match rtype {
+                "A" => RData::A(next_field(&mut rdata_fields, malformed)?),
+                "AAAA" => RData::AAAA(next_field(&mut rdata_fields, malformed)?),
+                "NS" => RData::NS(next_field(&mut rdata_fields, malformed)?),
+                "CNAME" => RData::CName(next_field(&mut rdata_fields, malformed)?),
+                "MX" => RData::MX {
+                    preference: next_field(&mut rdata_fields, malformed)?,
+                    exchange: next_field(&mut rdata_fields, malformed)?,
+                },
+                "SOA" => {
+                    let params = SoaParams {
+                        mname: next_field(&mut rdata_fields, malformed)?,
+                        rname: next_field(&mut rdata_fields, malformed)?,
+                        serial: next_field(&mut rdata_fields, malformed)?,
+                        refresh: next_field(&mut rdata_fields, malformed)?,
+                        retry: next_field(&mut rdata_fields, malformed)?,
+                        expire: next_field(&mut rdata_fields, malformed)?,
+                        minimum: next_field(&mut rdata_fields, malformed)?,
+                    };
+                    if apex.is_none() {
+                        apex = Some(name.clone());
+                        soa = Some(params.clone());
+                    }
+                    RData::SOA(params)
+                }
+                "TXT" => RData::TXT(vec![rdata_fields.collect::<Vec<_>>().join(" ")]),
+                _ => return Err(malformed()),
+            };
+
+            records
+                .entry((Self::key(&name), rdata.rrtype()))
+                .or_default()
+                .push(ResourceRecord {
+                    name,
+                    class: Class::IN,
+                    ttl,
+                    rdata,
+                });
+        }
+
+        Ok(Self {
+            apex: apex.ok_or(ZoneError::MissingSoa)?,
+            soa: soa.ok_or(ZoneError::MissingSoa)?,
+            records,
+        })
+    }
+
+    /// Case-fold `name` into the form used as a `records` key (DNS names
+    /// are case-insensitive, RFC1035 §2.3.3).
+    fn key(name: &CName) -> String {
+        name.to_string().to_ascii_lowercase()
+    }
+
+    /// Whether `name` is the apex or a name below it.
+    fn contains(&self, name: &CName) -> bool {
+        if name.0.len() < self.apex.0.len() {
+            return false;
+        }
+        let offset = name.0.len() - self.apex.0.len();
+        name.0[offset..]
+            .iter()
+            .map(|l| l.value.to_ascii_lowercase())
+            .eq(self.apex.0.iter().map(|l| l.value.to_ascii_lowercase()))
+    }
+
+    /// Whether any record exists for `name`, regardless of type.
+    fn exists(&self, name: &CName) -> bool {
+        let name = Self::key(name);
+        self.records.keys().any(|(owner, _)| owner == &name)
+    }
+
+    fn lookup(&self, name: &CName, rrtype: RRType) -> Vec<&ResourceRecord> {
+        self.records
+            .get(&(Self::key(name), rrtype))
+            .map(|records| records.iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// NS records for the apex (if any were loaded) plus the zone's own
+    /// SOA, for the authority section of a NODATA response.
+    fn authority_records(&self) -> Vec<ResourceRecord> {
+        let mut records = self
+            .records
+            .get(&(Self::key(&self.apex), RRType::NS))
+            .cloned()
+            .unwrap_or_default();
+        records.push(ResourceRecord {
+            name: self.apex.clone(),
+            class: Class::IN,
+            ttl: self.soa.minimum,
+            rdata: RData::SOA(self.soa.clone()),
+        });
+        records
+    }
+}
+
+/// Pull the next whitespace-separated field out of a zone file line and
+/// parse it, or report the whole line as malformed.
+fn next_field<'a, T: FromStr>(
+    fields: &mut impl Iterator<Item = &'a str>,
+    malformed: impl Fn() -> ZoneError,
+) -> Result<T, ZoneError> {
+    fields
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(malformed)
+}
+
+/// A single entry of a message's question section.
+#[derive(Debug, Clone)]
+struct Question {
+    qname: CName,
+    qtype: RRType,
+    qclass: Class,
+}
+
+impl Question {
+    fn from_bytes(buf: &[u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let (qname, pos) = CName::from_bytes(buf, offset)?;
+        let qtype = RRType::from_u16(u16::from_be_bytes(
+            buf.get(pos..pos + 2)
+                .ok_or(ParseError::UnexpectedEof)?
+                .try_into()
+                .unwrap(),
+        ));
+        let qclass = Class::from_u16(u16::from_be_bytes(
+            buf.get(pos + 2..pos + 4)
+                .ok_or(ParseError::UnexpectedEof)?
+                .try_into()
+                .unwrap(),
+        ));
+        Ok((
+            Self {
+                qname,
+                qtype,
+                qclass,
+            },
+            pos + 4,
+        ))
+    }
+
+    /// Encode QNAME, QTYPE and QCLASS, compressing QNAME against whatever
+    /// suffixes `table` already has recorded (see [`CName::to_bytes_compressed`]).
+    fn to_bytes(&self, offset: u16, table: &mut HashMap<String, u16>) -> Vec<u8> {
+        let mut r = self.qname.to_bytes_compressed(offset, table);
+        r.extend(self.qtype.to_bytes());
+        r.extend(self.qclass.to_bytes());
+        r
+    }
+}
+
+/// An EDNS0 OPT pseudo-RR (RFC6891 §6.1), carried in a message's additional
+/// section instead of a real resource record. Its wire layout reuses the
+/// generic RR fields for non-RR meanings: the CLASS field holds the
+/// requestor's UDP payload size and the TTL field packs an extended RCODE,
+/// the EDNS version and the flag bits (only DO, the "DNSSEC OK" bit, is
+/// used here).
+#[derive(Debug, Clone, Copy)]
+struct EdnsOpt {
+    udp_payload_size: u16,
+    extended_rcode: u8,
+    version: u8,
+    do_bit: bool,
+}
+
+impl EdnsOpt {
+    /// The payload size we advertise in our own responses.
+    const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+    fn ours() -> Self {
+        Self {
+            udp_payload_size: Self::DEFAULT_UDP_PAYLOAD_SIZE,
+            extended_rcode: 0,
+            version: 0,
+            do_bit: false,
+        }
+    }
+
+    /// Encode the full pseudo-RR: root NAME, TYPE OPT, and the CLASS/TTL
+    /// fields packed per RFC6891. We never emit any options, so RDLENGTH is
+    /// always zero.
+    fn to_bytes(self) -> Vec<u8> {
+        let mut r = vec![0]; // root NAME
+        r.extend(RRType::OPT.to_bytes());
+        r.extend(self.udp_payload_size.to_be_bytes());
+
+        let flags: u32 = if self.do_bit { 1 << 15 } else { 0 };
+        let ttl = ((self.extended_rcode as u32) << 24) | ((self.version as u32) << 16) | flags;
+        r.extend(ttl.to_be_bytes());
+
+        r.extend(0_u16.to_be_bytes()); // RDLENGTH
+        r
+    }
+
+    /// Decode an OPT pseudo-RR starting at `offset`, the inverse of
+    /// [`EdnsOpt::to_bytes`]. Any options present in RDATA are skipped.
+    fn from_bytes(buf: &[u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let (_, pos) = CName::from_bytes(buf, offset)?;
+        let udp_payload_size = u16::from_be_bytes(
+            buf.get(pos + 2..pos + 4)
+                .ok_or(ParseError::UnexpectedEof)?
+                .try_into()
+                .unwrap(),
+        );
+        let ttl = u32::from_be_bytes(
+            buf.get(pos + 4..pos + 8)
+                .ok_or(ParseError::UnexpectedEof)?
+                .try_into()
+                .unwrap(),
+        );
+        let rdlength = u16::from_be_bytes(
+            buf.get(pos + 8..pos + 10)
+                .ok_or(ParseError::UnexpectedEof)?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let rdata_end = pos + 10 + rdlength;
+        if buf.get(pos + 10..rdata_end).is_none() {
+            return Err(ParseError::UnexpectedEof);
+        }
+
+        Ok((
+            Self {
+                udp_payload_size,
+                extended_rcode: (ttl >> 24) as u8,
+                version: (ttl >> 16) as u8,
+                do_bit: ttl & (1 << 15) != 0,
+            },
+            rdata_end,
+        ))
+    }
+}
+
+/// A fully decoded DNS message: header, question section, answer section
+/// and whatever else (the authority section and an optional EDNS0 OPT
+/// pseudo-RR) the additional section carried.
+#[derive(Debug)]
+struct DNSMessage {
+    header: DNSHeader,
+    questions: Vec<Question>,
+    answers: Vec<ResourceRecord>,
+    authority: Vec<ResourceRecord>,
+    /// the OPT pseudo-RR pulled out of the additional section, if any
+    edns: Option<EdnsOpt>,
+}
+
+impl DNSMessage {
+    fn from_bytes(buf: &[u8]) -> Result<Self, ParseError> {
+        let header = DNSHeader::from_bytes(buf)?;
+
+        let mut pos = 12;
+        let mut questions = Vec::with_capacity(header.qdcount as usize);
+        for _ in 0..header.qdcount {
+            let (question, next) = Question::from_bytes(buf, pos)?;
+            questions.push(question);
+            pos = next;
+        }
+
+        let mut answers = Vec::with_capacity(header.ancount as usize);
+        for _ in 0..header.ancount {
+            let (answer, next) = ResourceRecord::from_bytes(buf, pos)?;
+            answers.push(answer);
+            pos = next;
+        }
+
+        let mut authority = Vec::with_capacity(header.nscount as usize);
+        for _ in 0..header.nscount {
+            let (record, next) = ResourceRecord::from_bytes(buf, pos)?;
+            authority.push(record);
+            pos = next;
+        }
+
+        // The additional section may carry an EDNS0 OPT pseudo-RR among
+        // whatever else is there; its CLASS/TTL fields don't mean what
+        // `ResourceRecord` expects them to, so it's peeled off by TYPE
+        // before falling back to the generic parse for anything else.
+        let mut edns = None;
+        for _ in 0..header.arcount {
+            let (_, name_end) = CName::from_bytes(buf, pos)?;
+            let rrtype = RRType::from_u16(u16::from_be_bytes(
+                buf.get(name_end..name_end + 2)
+                    .ok_or(ParseError::UnexpectedEof)?
+                    .try_into()
+                    .unwrap(),
+            ));
+            if rrtype == RRType::OPT {
+                let (opt, next) = EdnsOpt::from_bytes(buf, pos)?;
+                edns = Some(opt);
+                pos = next;
+            } else {
+                let (_, next) = ResourceRecord::from_bytes(buf, pos)?;
+                pos = next;
+            }
+        }
+
+        Ok(Self {
+            header,
+            questions,
+            answers,
+            authority,
+            edns,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut r = self.header.to_bytes().to_vec();
+        // Owner names repeat constantly (every answer restates the question's
+        // name, NODATA responses restate the zone apex, ...), so names are
+        // compressed against each other as they're written (RFC1035 §4.1.4).
+        let mut table = HashMap::new();
+        for question in &self.questions {
+            r.extend(question.to_bytes(r.len() as u16, &mut table));
+        }
+        for answer in &self.answers {
+            r.extend(answer.to_bytes(r.len() as u16, &mut table));
+        }
+        for record in &self.authority {
+            r.extend(record.to_bytes(r.len() as u16, &mut table));
+        }
+        if let Some(opt) = &self.edns {
+            r.extend(opt.to_bytes());
+        }
+        r
+    }
+}
+
+/// Pull the upstream address out of a `--resolver ADDR` flag, if present.
+fn parse_resolver_arg(mut args: impl Iterator<Item = String>) -> Option<String> {
+    while let Some(arg) = args.next() {
+        if arg == "--resolver" {
+            return args.next();
+        }
+    }
+    None
+}
+
+/// Relay `question` to `resolver` as a single-question query carrying
+/// `packet_id`, and return whatever answer records it sent back. Retries
+/// once so a single dropped UDP datagram doesn't hang the server. A reply
+/// is only trusted if it came from `resolver` itself and echoes back
+/// `packet_id`, so a stray datagram (or a late reply to an earlier retry)
+/// can't get matched to the wrong question.
+fn forward_question(resolver: &str, packet_id: u16, question: &Question) -> Option<Vec<ResourceRecord>> {
+    let resolver: SocketAddr = resolver.parse().ok()?;
+
+    let query = DNSMessage {
+        header: DNSHeader {
+            packet_id,
+            qr: false,
+            rd: true,
+            qdcount: 1,
+            ..DNSHeader::default()
+        },
+        questions: vec![question.clone()],
+        answers: Vec::new(),
+        authority: Vec::new(),
+        edns: None,
+    }
+    .to_bytes();
+
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
+
+    let mut buf = [0; 512];
+    for _ in 0..2 {
+        if socket.send_to(&query, resolver).is_err() {
+            continue;
+        }
+        if let Ok((size, source)) = socket.recv_from(&mut buf) {
+            if source != resolver {
+                continue;
+            }
+            if let Ok(response) = DNSMessage::from_bytes(&buf[..size]) {
+                if response.header.packet_id == packet_id {
+                    return Some(response.answers);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Build the reply to a parsed query. Each question is first checked
+/// against the loaded `zones`; anything outside every zone's apex falls
+/// through to the resolver, if one is configured.
+fn build_response(message: &DNSMessage, zones: &[Zone], resolver: Option<&str>) -> Vec<u8> {
+    if !matches!(message.header.opcode, OpCode::Query) {
+        return DNSHeader {
+            packet_id: message.header.packet_id,
+            opcode: message.header.opcode,
+            rcode: RCode::NotImp,
+            ..DNSHeader::default()
+        }
+        .to_bytes()
+        .to_vec();
+    }
+
+    let mut answers = Vec::new();
+    let mut authority = Vec::new();
+    let mut to_forward = Vec::new();
+    let mut authoritative = false;
+    let mut nxdomain = false;
+
+    for question in &message.questions {
+        match zones.iter().find(|zone| zone.contains(&question.qname)) {
+            Some(zone) => {
+                authoritative = true;
+                let matched = zone.lookup(&question.qname, question.qtype);
+                if !matched.is_empty() {
+                    answers.extend(matched.into_iter().cloned());
+                } else if zone.exists(&question.qname) {
+                    authority.extend(zone.authority_records());
+                } else {
+                    nxdomain = true;
+                }
+            }
+            None => to_forward.push(question.clone()),
+        }
+    }
+
+    let mut all_forwarded = true;
+    if let Some(resolver) = resolver {
+        for question in &to_forward {
+            match forward_question(resolver, message.header.packet_id, question) {
+                Some(mut forwarded) => answers.append(&mut forwarded),
+                None => all_forwarded = false,
+            }
+        }
+    }
+
+    let rcode = if nxdomain && answers.is_empty() {
+        RCode::NXDomain
+    } else if !all_forwarded {
+        RCode::ServFail
+    } else {
+        RCode::NoError
+    };
+
+    // RFC6891: only advertise our own EDNS0 support if the query did.
+    let edns = message.edns.map(|_| EdnsOpt::ours());
+
+    let header = DNSHeader {
+        packet_id: message.header.packet_id,
+        qr: true,
+        opcode: OpCode::Query,
+        aa: authoritative,
+        rd: message.header.rd,
+        ra: resolver.is_some(),
+        rcode,
+        qdcount: message.questions.len() as u16,
+        ancount: answers.len() as u16,
+        nscount: authority.len() as u16,
+        arcount: edns.is_some() as u16,
+        ..DNSHeader::default()
+    };
+
+    let mut response = DNSMessage {
+        header,
+        questions: message.questions.clone(),
+        answers,
+        authority,
+        edns,
+    };
+    let bytes = response.to_bytes();
+
+    // The classic limit is 512 bytes (RFC1035 §4.2.1); EDNS0 lets the
+    // client raise it via its own advertised payload size. Either way, a
+    // response that doesn't fit gets truncated down to header+question
+    // with TC set, so the client knows to retry over TCP.
+    let limit = message
+        .edns
+        .map_or(512, |opt| opt.udp_payload_size as usize);
+    if bytes.len() <= limit {
+        return bytes;
+    }
+
+    response.header.tc = true;
+    response.header.ancount = 0;
+    response.header.nscount = 0;
+    response.answers.clear();
+    response.authority.clear();
+    response.to_bytes()
+}
+
+/// Collect every `--zone FILE` occurrence in the process arguments.
+fn parse_zone_args(mut args: impl Iterator<Item = String>) -> Vec<String> {
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--zone" {
+            if let Some(path) = args.next() {
+                paths.push(path);
+            }
+        }
+    }
+    paths
 }
 
 fn main() {
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     println!("Logs from your program will appear here!");
 
+    let resolver = parse_resolver_arg(std::env::args());
+    let zones: Vec<Zone> = parse_zone_args(std::env::args())
+        .into_iter()
+        .filter_map(|path| match Zone::load(&path) {
+            Ok(zone) => Some(zone),
+            Err(e) => {
+                eprintln!("Failed to load zone file {}: {}", path, e);
+                None
+            }
+        })
+        .collect();
+
     // Uncomment this block to pass the first stage
     let udp_socket = UdpSocket::bind("127.0.0.1:2053").expect("Failed to bind to address");
-    let mut buf = [0; 512];
+    // Large enough for an EDNS0 query advertising our own default payload
+    // size, so recv_from doesn't silently truncate it ahead of parsing.
+    let mut buf = [0; EdnsOpt::DEFAULT_UDP_PAYLOAD_SIZE as usize];
 
     loop {
         match udp_socket.recv_from(&mut buf) {
             Ok((size, source)) => {
                 println!("Received {} bytes from {}", size, source);
-                let response = DNSHeader::default().to_bytes();
+                let response = match DNSMessage::from_bytes(&buf[..size]) {
+                    Ok(message) => build_response(&message, &zones, resolver.as_deref()),
+                    Err(_) => DNSHeader::default().to_bytes().to_vec(),
+                };
                 udp_socket
                     .send_to(&response, source)
                     .expect("Failed to send response");